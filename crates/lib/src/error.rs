@@ -0,0 +1,13 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum KoraError {
+    #[error("RPC error: {0}")]
+    RpcError(String),
+
+    #[error("invalid transaction: {0}")]
+    InvalidTransaction(String),
+
+    #[error("price unavailable for {0}")]
+    PriceUnavailable(String),
+}
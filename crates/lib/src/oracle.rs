@@ -0,0 +1,283 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+
+use crate::{error::KoraError, transaction::fees::TokenPriceInfo};
+
+/// Default ratio of confidence interval to price above which a quote is
+/// rejected as too uncertain to price a fee with.
+const DEFAULT_MAX_CONFIDENCE_RATIO: f64 = 0.02;
+
+/// Default maximum age of a price observation before it is considered stale.
+const DEFAULT_MAX_STALENESS: Duration = Duration::from_secs(30);
+
+/// A single price observation returned by an upstream price source, carrying
+/// enough metadata to judge whether it's trustworthy enough to price a fee.
+#[derive(Debug, Clone)]
+pub struct PriceQuote {
+    pub price: f64,
+    pub confidence: f64,
+    pub observed_at_unix: i64,
+}
+
+/// A source of token price quotes. `PriceOracle` queries an ordered list of
+/// these, falling back to the next source when a quote fails the
+/// confidence/staleness checks.
+#[async_trait]
+pub trait PriceSource: Send + Sync {
+    async fn fetch_price(&self, token: &str) -> Result<PriceQuote, KoraError>;
+}
+
+/// Queries an ordered chain of [`PriceSource`]s for a token price, rejecting
+/// quotes that are too uncertain or too stale and falling through to the next
+/// source. The last source in the chain is gated like the others; when it
+/// also fails the checks and the chain has more than one source, its quote is
+/// returned anyway, so the oracle can still return a price when every other
+/// source is degraded. A single-source chain fails closed instead.
+pub struct PriceOracle {
+    sources: Vec<Box<dyn PriceSource>>,
+    retries: u32,
+    timeout: Duration,
+    max_confidence_ratio: f64,
+    max_staleness: Duration,
+}
+
+impl PriceOracle {
+    pub fn new(retries: u32, timeout: Duration) -> Self {
+        Self {
+            sources: vec![Box::new(JupiterPriceSource)],
+            retries,
+            timeout,
+            max_confidence_ratio: DEFAULT_MAX_CONFIDENCE_RATIO,
+            max_staleness: DEFAULT_MAX_STALENESS,
+        }
+    }
+
+    /// Overrides the default single-source chain with an explicit, ordered
+    /// fallback list.
+    pub fn with_sources(mut self, sources: Vec<Box<dyn PriceSource>>) -> Self {
+        self.sources = sources;
+        self
+    }
+
+    pub fn with_confidence_ratio(mut self, max_confidence_ratio: f64) -> Self {
+        self.max_confidence_ratio = max_confidence_ratio;
+        self
+    }
+
+    pub fn with_max_staleness(mut self, max_staleness: Duration) -> Self {
+        self.max_staleness = max_staleness;
+        self
+    }
+
+    pub async fn get_token_price(&self, token: &str) -> Result<TokenPriceInfo, KoraError> {
+        let (last, primaries) = match self.sources.split_last() {
+            Some(split) => split,
+            None => return Err(KoraError::PriceUnavailable(token.to_string())),
+        };
+
+        for source in primaries {
+            if let Ok(quote) = self.fetch_with_retries(source.as_ref(), token).await {
+                if check_confidence_and_maybe_staleness(
+                    &quote,
+                    self.max_confidence_ratio,
+                    self.max_staleness,
+                ) {
+                    return Ok(TokenPriceInfo { price: quote.price });
+                }
+            }
+        }
+
+        // The designated fallback source is first gated like any other source;
+        // a quote that passes is returned as is. The ungated fallback below
+        // applies only when `primaries` is non-empty, so a single-source oracle
+        // fails closed just like every other rejected source.
+        let fallback_quote = self.fetch_with_retries(last.as_ref(), token).await.ok();
+        if let Some(quote) = &fallback_quote {
+            if check_confidence_and_maybe_staleness(
+                quote,
+                self.max_confidence_ratio,
+                self.max_staleness,
+            ) {
+                return Ok(TokenPriceInfo { price: quote.price });
+            }
+        }
+
+        // Every source, including the fallback, was unreachable or failed the
+        // gate. Only trust the fallback's ungated quote unconditionally when
+        // there were additional primary sources configured — a single-source
+        // chain has no further fallback to reach for and must fail closed.
+        if !primaries.is_empty() {
+            if let Some(quote) = fallback_quote {
+                return Ok(TokenPriceInfo { price: quote.price });
+            }
+        }
+
+        Err(KoraError::PriceUnavailable(token.to_string()))
+    }
+
+    async fn fetch_with_retries(
+        &self,
+        source: &dyn PriceSource,
+        token: &str,
+    ) -> Result<PriceQuote, KoraError> {
+        let mut last_err = KoraError::PriceUnavailable(token.to_string());
+
+        for attempt in 0..=self.retries {
+            if attempt > 0 {
+                tokio::time::sleep(self.timeout).await;
+            }
+
+            match source.fetch_price(token).await {
+                Ok(quote) => return Ok(quote),
+                Err(e) => last_err = e,
+            }
+        }
+
+        Err(last_err)
+    }
+}
+
+/// Rejects a quote when its confidence interval is too wide relative to its
+/// price, or when it's older than `max_staleness`.
+fn check_confidence_and_maybe_staleness(
+    quote: &PriceQuote,
+    max_confidence_ratio: f64,
+    max_staleness: Duration,
+) -> bool {
+    if quote.price <= 0.0 {
+        return false;
+    }
+
+    let confidence_ratio = quote.confidence / quote.price;
+    if confidence_ratio > max_confidence_ratio {
+        return false;
+    }
+
+    let now_unix =
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+    let age_secs = now_unix - quote.observed_at_unix;
+
+    age_secs >= 0 && age_secs as u64 <= max_staleness.as_secs()
+}
+
+/// Default price source backed by the Jupiter price API.
+struct JupiterPriceSource;
+
+#[async_trait]
+impl PriceSource for JupiterPriceSource {
+    async fn fetch_price(&self, token: &str) -> Result<PriceQuote, KoraError> {
+        #[derive(serde::Deserialize)]
+        struct JupiterPriceResponse {
+            data: std::collections::HashMap<String, JupiterPriceData>,
+        }
+
+        #[derive(serde::Deserialize)]
+        struct JupiterPriceData {
+            price: f64,
+        }
+
+        let url = format!("https://price.jup.ag/v4/price?ids={}", token);
+        let response = reqwest::get(&url)
+            .await
+            .map_err(|e| KoraError::RpcError(format!("Jupiter price request failed: {}", e)))?
+            .json::<JupiterPriceResponse>()
+            .await
+            .map_err(|e| KoraError::RpcError(format!("Jupiter price response invalid: {}", e)))?;
+
+        let data = response
+            .data
+            .get(token)
+            .ok_or_else(|| KoraError::PriceUnavailable(token.to_string()))?;
+
+        let now_unix =
+            SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+
+        // Jupiter's v4 price API doesn't report a confidence interval or an
+        // observation time; treat the quote as tight and stamp it with the fetch
+        // time, so it passes both checks unless its price is not positive.
+        Ok(PriceQuote { price: data.price, confidence: 0.0, observed_at_unix: now_unix })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now_unix() -> i64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+    }
+
+    #[test]
+    fn rejects_low_confidence_quote() {
+        let quote = PriceQuote { price: 100.0, confidence: 5.0, observed_at_unix: now_unix() };
+        assert!(!check_confidence_and_maybe_staleness(&quote, 0.02, Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn rejects_stale_quote() {
+        let quote =
+            PriceQuote { price: 100.0, confidence: 0.1, observed_at_unix: now_unix() - 60 };
+        assert!(!check_confidence_and_maybe_staleness(&quote, 0.02, Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn accepts_fresh_confident_quote() {
+        let quote = PriceQuote { price: 100.0, confidence: 0.1, observed_at_unix: now_unix() };
+        assert!(check_confidence_and_maybe_staleness(&quote, 0.02, Duration::from_secs(30)));
+    }
+
+    struct FakeSource {
+        quote: PriceQuote,
+    }
+
+    #[async_trait]
+    impl PriceSource for FakeSource {
+        async fn fetch_price(&self, _token: &str) -> Result<PriceQuote, KoraError> {
+            Ok(self.quote.clone())
+        }
+    }
+
+    fn oracle_with_sources(sources: Vec<Box<dyn PriceSource>>) -> PriceOracle {
+        PriceOracle::new(0, Duration::from_millis(1)).with_sources(sources)
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_good_secondary_when_primary_is_stale() {
+        let stale = FakeSource {
+            quote: PriceQuote { price: 100.0, confidence: 0.1, observed_at_unix: now_unix() - 3600 },
+        };
+        let good = FakeSource {
+            quote: PriceQuote { price: 105.0, confidence: 0.1, observed_at_unix: now_unix() },
+        };
+
+        let oracle = oracle_with_sources(vec![Box::new(stale), Box::new(good)]);
+        let price = oracle.get_token_price("SOL").await.unwrap();
+        assert_eq!(price.price, 105.0);
+    }
+
+    #[tokio::test]
+    async fn single_source_still_fails_closed_on_stale_quote() {
+        let stale = FakeSource {
+            quote: PriceQuote { price: 100.0, confidence: 0.1, observed_at_unix: now_unix() - 3600 },
+        };
+
+        let oracle = oracle_with_sources(vec![Box::new(stale)]);
+        let result = oracle.get_token_price("SOL").await;
+        assert!(matches!(result, Err(KoraError::PriceUnavailable(_))));
+    }
+
+    #[tokio::test]
+    async fn multi_source_falls_back_unconditionally_when_all_degraded() {
+        let stale = FakeSource {
+            quote: PriceQuote { price: 100.0, confidence: 0.1, observed_at_unix: now_unix() - 3600 },
+        };
+        let also_stale = FakeSource {
+            quote: PriceQuote { price: 110.0, confidence: 0.1, observed_at_unix: now_unix() - 3600 },
+        };
+
+        let oracle = oracle_with_sources(vec![Box::new(stale), Box::new(also_stale)]);
+        let price = oracle.get_token_price("SOL").await.unwrap();
+        assert_eq!(price.price, 110.0);
+    }
+}
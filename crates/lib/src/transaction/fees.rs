@@ -3,8 +3,11 @@ use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::{
     native_token::LAMPORTS_PER_SOL, program_pack::Pack, pubkey::Pubkey, transaction::Transaction,
 };
-use spl_associated_token_account::get_associated_token_address;
+use spl_associated_token_account::get_associated_token_address_with_program_id;
 use spl_token::state::{Account as TokenAccount, Mint};
+use spl_token_2022::extension::{
+    transfer_fee::TransferFeeConfig, BaseStateWithExtensions, ExtensionType, StateWithExtensions,
+};
 use std::time::Duration;
 use utoipa::ToSchema;
 
@@ -15,9 +18,53 @@ pub struct TokenPriceInfo {
     pub price: f64,
 }
 
+/// A priced fee quote denominated in a token, with a human-readable amount
+/// alongside the raw integer units so API consumers don't have to re-derive
+/// decimals themselves.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TokenFeeQuote {
+    pub raw_amount: u64,
+    pub ui_amount_string: String,
+    pub lamports: u64,
+}
+
+/// Formats a raw token amount as a fixed-point decimal string using only
+/// integer arithmetic, avoiding the floating-point error that creeps in when
+/// dividing by `10f64.powi(decimals)`.
+fn format_token_amount(raw_amount: u64, decimals: u8) -> String {
+    let decimals = decimals as usize;
+    let digits = raw_amount.to_string();
+
+    if decimals == 0 {
+        return digits;
+    }
+
+    let padded = if digits.len() <= decimals {
+        format!("{:0>width$}", digits, width = decimals + 1)
+    } else {
+        digits
+    };
+
+    let split_at = padded.len() - decimals;
+    format!("{}.{}", &padded[..split_at], &padded[split_at..])
+}
+
+/// Same as [`format_token_amount`], but strips trailing zeros (and a dangling
+/// decimal point) for display purposes.
+fn format_token_amount_trimmed(raw_amount: u64, decimals: u8) -> String {
+    let formatted = format_token_amount(raw_amount, decimals);
+    if !formatted.contains('.') {
+        return formatted;
+    }
+
+    formatted.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
 pub async fn estimate_transaction_fee(
     rpc_client: &RpcClient,
     transaction: &Transaction,
+    priority_fee_percentile: u8,
+    max_priority_fee_lamports: u64,
 ) -> Result<u64, KoraError> {
     // Get base transaction fee
     let base_fee = rpc_client
@@ -30,24 +77,107 @@ pub async fn estimate_transaction_fee(
         .await
         .map_err(|e| KoraError::RpcError(e.to_string()))?;
 
-    // Get priority fee from recent blocks
+    // Scope the prioritization fee sample to the accounts this transaction actually
+    // writes to, rather than the global `&[]` sample, so the estimate reflects
+    // contention on the accounts that matter for this transaction.
+    let writable_keys: Vec<Pubkey> = transaction
+        .message
+        .account_keys
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| transaction.message.is_writable(*index))
+        .map(|(_, key)| *key)
+        .collect();
+
     let priority_stats = rpc_client
-        .get_recent_prioritization_fees(&[])
+        .get_recent_prioritization_fees(&writable_keys)
         .await
         .map_err(|e| KoraError::RpcError(e.to_string()))?;
-    let priority_fee = priority_stats.iter().map(|fee| fee.prioritization_fee).max().unwrap_or(0);
+
+    // Vote-only activity reports a prioritization fee of 0, so excluding zero
+    // samples keeps the percentile estimate representative of fee-paying traffic.
+    let samples: Vec<u64> = priority_stats
+        .iter()
+        .map(|fee| fee.prioritization_fee)
+        .filter(|&fee| fee > 0)
+        .collect();
+
+    let priority_fee =
+        percentile_priority_fee(&samples, priority_fee_percentile).min(max_priority_fee_lamports);
 
     Ok(base_fee + priority_fee + account_creation_fee)
 }
 
+/// Computes the `percentile`-th value of `samples` using linear interpolation
+/// between the two nearest ranks, matching the convention used for block-level
+/// prioritization fee statistics. Returns 0 when `samples` is empty.
+/// `percentile` is clamped to `0..=100` so an out-of-range value can't index
+/// past the end of `sorted`.
+fn percentile_priority_fee(samples: &[u64], percentile: u8) -> u64 {
+    if samples.is_empty() {
+        return 0;
+    }
+
+    let percentile = percentile.min(100);
+
+    let mut sorted = samples.to_vec();
+    sorted.sort_unstable();
+
+    let last_index = sorted.len() - 1;
+    let rank = (percentile as f64 / 100.0) * last_index as f64;
+    let lower = rank.floor() as usize;
+    let upper = (lower + 1).min(last_index);
+    let fraction = rank - lower as f64;
+
+    let lower_value = sorted[lower] as f64;
+    let upper_value = sorted[upper] as f64;
+
+    (lower_value + fraction * (upper_value - lower_value)).round() as u64
+}
+
+/// Maximum number of accounts `get_multiple_accounts` accepts per call.
+const MAX_ACCOUNTS_PER_RPC_CALL: usize = 100;
+
+/// Decimals and Token-2022 extension state unpacked from a mint account, used
+/// to handle legacy `spl_token` and `spl_token_2022` mints uniformly.
+struct MintInfo {
+    decimals: u8,
+    transfer_fee_config: Option<TransferFeeConfig>,
+    extension_types: Vec<ExtensionType>,
+}
+
+/// Unpacks a mint account, dispatching to the Token-2022 extension-aware
+/// layout when the account is owned by `spl_token_2022` rather than the
+/// legacy `spl_token` program.
+fn unpack_mint(owner: &Pubkey, data: &[u8]) -> Result<MintInfo, KoraError> {
+    if *owner == spl_token_2022::id() {
+        let mint_state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(data)
+            .map_err(|e| KoraError::InvalidTransaction(format!("Invalid mint: {}", e)))?;
+
+        let transfer_fee_config = mint_state.get_extension::<TransferFeeConfig>().ok().cloned();
+        let extension_types = mint_state
+            .get_extension_types()
+            .map_err(|e| KoraError::InvalidTransaction(format!("Invalid mint: {}", e)))?;
+
+        Ok(MintInfo { decimals: mint_state.base.decimals, transfer_fee_config, extension_types })
+    } else {
+        let mint = Mint::unpack(data)
+            .map_err(|e| KoraError::InvalidTransaction(format!("Invalid mint: {}", e)))?;
+
+        Ok(MintInfo { decimals: mint.decimals, transfer_fee_config: None, extension_types: Vec::new() })
+    }
+}
+
 async fn get_associated_token_account_creation_fees(
     rpc_client: &RpcClient,
     transaction: &Transaction,
 ) -> Result<u64, KoraError> {
-    const ATA_ACCOUNT_SIZE: usize = TokenAccount::LEN;
-    let mut ata_count = 0u64;
+    // Collect every candidate ATA and its mint from the transaction's
+    // instructions first, so existence can be checked in batches instead of
+    // one round trip per account.
+    let mut candidate_atas = Vec::new();
+    let mut candidate_mints = Vec::new();
 
-    // Check each instruction in the transaction for ATA creation
     for instruction in &transaction.message.instructions {
         let program_id = transaction.message.account_keys[instruction.program_id_index as usize];
 
@@ -56,57 +186,126 @@ async fn get_associated_token_account_creation_fees(
             continue;
         }
 
+        // `transaction` is an arbitrary, possibly attacker-supplied transaction
+        // being priced, not one the relayer wrote itself — a malformed
+        // instruction claiming to be the ATA program but carrying fewer than
+        // the 6 accounts `Create`/`CreateIdempotent` require must be skipped
+        // rather than indexed into and panicking the relayer.
+        if instruction.accounts.len() < 6 {
+            continue;
+        }
+
         let ata = transaction.message.account_keys[instruction.accounts[1] as usize];
         let owner = transaction.message.account_keys[instruction.accounts[2] as usize];
         let mint = transaction.message.account_keys[instruction.accounts[3] as usize];
+        // `Create`/`CreateIdempotent` carry the owning token program as the 6th
+        // account, which is `spl_token_2022::id()` for Token-2022 ATAs; deriving
+        // against the legacy program unconditionally would never match those.
+        let token_program = transaction.message.account_keys[instruction.accounts[5] as usize];
 
-        let expected_ata = get_associated_token_address(&owner, &mint);
+        let expected_ata =
+            get_associated_token_address_with_program_id(&owner, &mint, &token_program);
 
-        if ata == expected_ata && rpc_client.get_account(&ata).await.is_err() {
-            ata_count += 1;
+        if ata == expected_ata {
+            candidate_atas.push(ata);
+            candidate_mints.push(mint);
         }
     }
 
-    // Get rent cost in lamports for ATA creation
-    use solana_sdk::rent::Rent;
-    let rent = Rent::default();
-    let exempt_min = rent.minimum_balance(ATA_ACCOUNT_SIZE);
+    let rent = solana_sdk::rent::Rent::default();
+    let mut lamports = 0u64;
+
+    for (ata_chunk, mint_chunk) in candidate_atas
+        .chunks(MAX_ACCOUNTS_PER_RPC_CALL)
+        .zip(candidate_mints.chunks(MAX_ACCOUNTS_PER_RPC_CALL))
+    {
+        let ata_accounts = rpc_client
+            .get_multiple_accounts(ata_chunk)
+            .await
+            .map_err(|e| KoraError::RpcError(e.to_string()))?;
+        let mint_accounts = rpc_client
+            .get_multiple_accounts(mint_chunk)
+            .await
+            .map_err(|e| KoraError::RpcError(e.to_string()))?;
 
-    Ok(exempt_min * ata_count)
+        for ((ata_account, mint_account), mint_pubkey) in
+            ata_accounts.iter().zip(mint_accounts.iter()).zip(mint_chunk.iter())
+        {
+            // ATA already exists, nothing to create.
+            if ata_account.is_some() {
+                continue;
+            }
+
+            let mint_account = mint_account.as_ref().ok_or_else(|| {
+                KoraError::InvalidTransaction(format!("Mint account {} not found", mint_pubkey))
+            })?;
+
+            let account_len = if mint_account.owner == spl_token_2022::id() {
+                let mint_info = unpack_mint(&mint_account.owner, &mint_account.data)?;
+                let required_extensions =
+                    ExtensionType::get_required_init_account_extensions(&mint_info.extension_types);
+
+                ExtensionType::try_calculate_account_len::<spl_token_2022::state::Account>(
+                    &required_extensions,
+                )
+                .map_err(|e| {
+                    KoraError::InvalidTransaction(format!("Invalid mint extensions: {}", e))
+                })?
+            } else {
+                TokenAccount::LEN
+            };
+
+            lamports += rent.minimum_balance(account_len);
+        }
+    }
+
+    Ok(lamports)
 }
 
 pub async fn calculate_token_value_in_lamports(
     amount: u64,
     mint: &Pubkey,
     rpc_client: &RpcClient,
-) -> Result<u64, KoraError> {
-    // Fetch mint account data to determine token decimals
+) -> Result<TokenFeeQuote, KoraError> {
+    // Fetch mint account data to determine token decimals and, for
+    // Token-2022 mints, any transfer-fee extension.
     let mint_account =
         rpc_client.get_account(mint).await.map_err(|e| KoraError::RpcError(e.to_string()))?;
 
-    let mint_data = Mint::unpack(&mint_account.data)
-        .map_err(|e| KoraError::InvalidTransaction(format!("Invalid mint: {}", e)))?;
+    let mint_info = unpack_mint(&mint_account.owner, &mint_account.data)?;
+
+    // A Token-2022 transfer withholds a fee on the recipient's side, so price
+    // the net amount the relayer will actually receive rather than the gross
+    // transfer amount.
+    let amount = if let Some(transfer_fee_config) = &mint_info.transfer_fee_config {
+        let epoch = rpc_client
+            .get_epoch_info()
+            .await
+            .map_err(|e| KoraError::RpcError(e.to_string()))?
+            .epoch;
+        let withheld_fee = transfer_fee_config.calculate_epoch_fee(epoch, amount).unwrap_or(0);
+        amount.saturating_sub(withheld_fee)
+    } else {
+        amount
+    };
 
-    // Initialize price oracle with retries for reliability
+    // Initialize price oracle with retries for reliability. The oracle fails
+    // closed with KoraError::PriceUnavailable when every configured source is
+    // unreachable or stale/low-confidence, so fee calculation doesn't mis-price
+    // on a bad quote.
     let oracle = PriceOracle::new(3, Duration::from_secs(1));
 
     // Fetch token price in USD
-    let token_price = oracle
-        .get_token_price(&mint.to_string())
-        .await
-        .map_err(|e| KoraError::RpcError(format!("Failed to fetch token price: {}", e)))?;
+    let token_price = oracle.get_token_price(&mint.to_string()).await?;
 
     // Fetch SOL price in USD (required for conversion)
-    let sol_price = oracle
-        .get_token_price("SOL")
-        .await
-        .map_err(|e| KoraError::RpcError(format!("Failed to fetch SOL price: {}", e)))?;
+    let sol_price = oracle.get_token_price("SOL").await?;
 
     // Use the constant from Solana SDK
     use solana_sdk::native_token::LAMPORTS_PER_SOL;
 
     // Convert token amount to its real value based on decimals
-    let token_amount = amount as f64 / 10f64.powi(mint_data.decimals as i32);
+    let token_amount = amount as f64 / 10f64.powi(mint_info.decimals as i32);
 
     // Compute token value in USD
     let usd_value = token_amount * token_price.price;
@@ -117,5 +316,75 @@ pub async fn calculate_token_value_in_lamports(
     // Convert SOL to lamports and round down
     let lamports = (sol_amount * LAMPORTS_PER_SOL as f64).floor() as u64;
 
-    Ok(lamports)
+    Ok(TokenFeeQuote {
+        raw_amount: amount,
+        ui_amount_string: format_token_amount_trimmed(amount, mint_info.decimals),
+        lamports,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentile_priority_fee_boundaries() {
+        let samples = vec![100, 200, 300, 400, 500];
+
+        assert_eq!(percentile_priority_fee(&samples, 0), 100);
+        assert_eq!(percentile_priority_fee(&samples, 50), 300);
+        assert_eq!(percentile_priority_fee(&samples, 100), 500);
+    }
+
+    #[test]
+    fn percentile_priority_fee_interpolates() {
+        let samples = vec![100, 200, 300, 400];
+
+        // rank = 0.75 * 3 = 2.25 -> 300 + 0.25 * (400 - 300) = 325
+        assert_eq!(percentile_priority_fee(&samples, 75), 325);
+    }
+
+    #[test]
+    fn percentile_priority_fee_clamps_out_of_range() {
+        let samples = vec![100, 200, 300, 400, 500];
+
+        // Without clamping this would index past the end of `sorted`.
+        assert_eq!(percentile_priority_fee(&samples, 150), percentile_priority_fee(&samples, 100));
+        assert_eq!(percentile_priority_fee(&samples, 255), 500);
+    }
+
+    #[test]
+    fn percentile_priority_fee_empty_samples() {
+        assert_eq!(percentile_priority_fee(&[], 50), 0);
+    }
+
+    #[test]
+    fn format_token_amount_basic() {
+        assert_eq!(format_token_amount(1_250_000, 6), "1.250000");
+    }
+
+    #[test]
+    fn format_token_amount_zero_decimals() {
+        assert_eq!(format_token_amount(42, 0), "42");
+    }
+
+    #[test]
+    fn format_token_amount_pads_leading_zeros() {
+        assert_eq!(format_token_amount(5, 6), "0.000005");
+    }
+
+    #[test]
+    fn format_token_amount_trimmed_strips_trailing_zeros() {
+        assert_eq!(format_token_amount_trimmed(1_250_000, 6), "1.25");
+    }
+
+    #[test]
+    fn format_token_amount_trimmed_strips_dangling_point() {
+        assert_eq!(format_token_amount_trimmed(1_000_000, 6), "1");
+    }
+
+    #[test]
+    fn format_token_amount_trimmed_zero_decimals_unchanged() {
+        assert_eq!(format_token_amount_trimmed(42, 0), "42");
+    }
 }